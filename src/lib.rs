@@ -6,50 +6,86 @@
         unused_import_braces, unused_qualifications)]
 //! A collection of approximate frequency counting algorithms for rust
 extern crate rand;
-use std::collections::HashMap;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde_derive;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use rand::{thread_rng, Rng};
+use rand::rngs::ThreadRng;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "metrics")]
+use std::thread;
+#[cfg(feature = "metrics")]
+use std::time::Duration;
 
+/// Types whose state can be snapshotted to bytes and restored later.
+#[cfg(feature = "serde")]
+pub trait Checkpoint: Sized {
+    /// serialize this value's current state to bytes.
+    fn to_bytes(&self) -> serde_json::Result<Vec<u8>>;
+    /// restore a value previously serialized with `to_bytes`.
+    fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self>;
+}
 
-/// trait for things which can count values
-pub trait Counter {
+/// trait for things which can count values keyed by `K`, eg. `u32` IP
+/// addresses, without paying a `to_string()` allocation on every `observe`.
+pub trait Counter<K: Hash + Eq + Clone> {
     /// `observe` tracks a value
-    fn observe(&mut self, key: &str);
+    fn observe(&mut self, key: &K);
     /// `items_above_threshold` return entries above threshold
-    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry>;
+    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry<K>>;
+    /// `merge` folds the counts observed by `other` into `self`, as if `self`
+    /// had observed both streams, so sharded counters can be combined.
+    fn merge(&mut self, other: &Self);
 }
 
 /// `Entry` tracks a key and it's frequency.
 #[derive(Debug)]
-pub struct Entry {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Entry<K> {
     /// The observed value
-    pub key: String,
+    pub key: K,
     /// The approximate frequency of the observed value on the interval (0,1]
     pub frequency: f64,
 }
 
 /// `NaiveSampler` is a reference exact counting implementation. I requires O(n) memory.
-#[derive(Default,Debug)]
-pub struct NaiveSampler {
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NaiveSampler<K: Hash + Eq> {
     n: u64,
-    vals: HashMap<String, u64>,
+    vals: HashMap<K, u64>,
 }
 
 /// Construct a new `NaiveSampler`
-pub fn new_naive_sampler() -> NaiveSampler {
+pub fn new_naive_sampler<K: Hash + Eq>() -> NaiveSampler<K> {
     NaiveSampler {
         n: 0,
         vals: HashMap::new(),
     }
 }
 
-impl Counter for NaiveSampler {
+impl<K: Hash + Eq + Clone> Counter<K> for NaiveSampler<K> {
     /// record that the given key has been observed.
-    fn observe(&mut self, key: &str) {
+    fn observe(&mut self, key: &K) {
         self.n += 1;
-        *self.vals.entry(key.to_string()).or_insert(0) += 1;
+        *self.vals.entry(key.clone()).or_insert(0) += 1;
     }
     /// return items who's frequency exceeds threshld
-    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry> {
+    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry<K>> {
         let count: u64 = ((self.n as f64) * threshold) as u64;
         self.vals
             .iter()
@@ -62,9 +98,28 @@ impl Counter for NaiveSampler {
             })
             .collect()
     }
+    /// fold `other`'s counts into `self`, summing the observation count and
+    /// each key's tally.
+    fn merge(&mut self, other: &Self) {
+        self.n += other.n;
+        for (key, val) in &other.vals {
+            *self.vals.entry(key.clone()).or_insert(0) += *val;
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: Hash + Eq + Serialize + DeserializeOwned> Checkpoint for NaiveSampler<K> {
+    fn to_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+    }
+    fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 struct FDeltaPair {
     f: f64,
     delta: f64,
@@ -74,15 +129,16 @@ struct FDeltaPair {
 /// `LossyCounter` implements the lossy counter outlined here
 /// http://www.vldb.org/conf/2002/S10P03.pdf
 #[derive(Debug)]
-pub struct LossyCounter {
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct LossyCounter<K: Hash + Eq> {
     support: f64,
-    d: HashMap<String, FDeltaPair>,
+    d: HashMap<K, FDeltaPair>,
     n: u64,
     bucket_width: u64,
 }
 
 /// `new_lossy_counter` constructs a counter with the given support and error tolerance
-pub fn new_lossy_counter(support: f64, error_tolerance: f64) -> LossyCounter {
+pub fn new_lossy_counter<K: Hash + Eq>(support: f64, error_tolerance: f64) -> LossyCounter<K> {
     LossyCounter {
         support: support,
         d: HashMap::new(),
@@ -90,10 +146,10 @@ pub fn new_lossy_counter(support: f64, error_tolerance: f64) -> LossyCounter {
         n: 0,
     }
 }
-impl LossyCounter {
+impl<K: Hash + Eq + Clone> LossyCounter<K> {
     fn prune(&mut self, bucket: u64) {
         let fbucket = bucket as f64;
-        let to_remove: Vec<String> = self.d
+        let to_remove: Vec<K> = self.d
             .iter()
             .filter(|&(_, value)| value.f + value.delta <= fbucket)
             .map(|(key, _)| key.clone())
@@ -102,10 +158,15 @@ impl LossyCounter {
             self.d.remove(key);
         }
     }
+    /// the bucket `observe` placed the most recent item in (1 before any
+    /// item has been observed)
+    fn current_bucket(&self) -> u64 {
+        (self.n / self.bucket_width) + 1
+    }
 }
-impl Counter for LossyCounter {
+impl<K: Hash + Eq + Clone> Counter<K> for LossyCounter<K> {
     /// return items who's frequency exceeds threshld
-    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry> {
+    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry<K>> {
         let f_n = self.n as f64;
         self.d
             .iter()
@@ -120,7 +181,7 @@ impl Counter for LossyCounter {
 
     }
     /// record that the given key has been observed.
-    fn observe(&mut self, key: &str) {
+    fn observe(&mut self, key: &K) {
         self.n += 1;
         let bucket = (self.n / self.bucket_width) + 1;
         let newval = match self.d.get(key) {
@@ -132,28 +193,80 @@ impl Counter for LossyCounter {
                 }
             }
         };
-        self.d.insert(key.to_string(), newval);
+        self.d.insert(key.clone(), newval);
         if self.n % self.bucket_width == 0 {
             self.prune(bucket);
         }
     }
+    /// fold `other`'s counts into `self`, assuming both share the same
+    /// `support`/`bucket_width`. A key missing from one side is treated as
+    /// having that side's maximum possible delta (`current_bucket - 1`).
+    fn merge(&mut self, other: &Self) {
+        let self_max_delta = (self.current_bucket() - 1) as f64;
+        let other_max_delta = (other.current_bucket() - 1) as f64;
+        let keys: HashSet<K> = self.d.keys().cloned().chain(other.d.keys().cloned()).collect();
+        let mut merged = HashMap::new();
+        for key in keys {
+            let (f_a, delta_a) = match self.d.get(&key) {
+                Some(val) => (val.f, val.delta),
+                None => (0.0, self_max_delta),
+            };
+            let (f_b, delta_b) = match other.d.get(&key) {
+                Some(val) => (val.f, val.delta),
+                None => (0.0, other_max_delta),
+            };
+            merged.insert(key,
+                          FDeltaPair {
+                              f: f_a + f_b,
+                              delta: delta_a + delta_b,
+                          });
+        }
+        self.d = merged;
+        self.n += other.n;
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: Hash + Eq + Serialize + DeserializeOwned> Checkpoint for LossyCounter<K> {
+    fn to_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+    }
+    fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
 }
 
 /// `StickySampler` implements an approximate frequency counting algorithm outlined here
 /// http://www.vldb.org/conf/2002/S10P03.pdf
+///
+/// Sampling is driven by the injected `R: Rng`, so a seeded `rng` makes a run reproducible.
 #[derive(Debug)]
-pub struct StickySampler {
+pub struct StickySampler<K: Hash + Eq, R: Rng> {
     error_tolerance: f64,
     support: f64,
-    s: HashMap<String, f64>,
+    s: HashMap<K, f64>,
     r: f64,
     n: f64,
     t: f64,
+    rng: R,
 }
 
 /// `new_sampler` returns a new sticky sampler with the given
-/// `support`, `error_tolerance`, and failure probability
-pub fn new_sampler(support: f64, error_tolerance: f64, failure_prob: f64) -> StickySampler {
+/// `support`, `error_tolerance`, and failure probability, using a thread-local RNG.
+pub fn new_sampler<K: Hash + Eq>(support: f64,
+                                  error_tolerance: f64,
+                                  failure_prob: f64)
+                                  -> StickySampler<K, ThreadRng> {
+    new_sampler_with_rng(support, error_tolerance, failure_prob, thread_rng())
+}
+
+/// `new_sampler_with_rng` returns a new sticky sampler with the given
+/// `support`, `error_tolerance`, and failure probability, sampling with `rng`.
+pub fn new_sampler_with_rng<K: Hash + Eq, R: Rng>(support: f64,
+                                                   error_tolerance: f64,
+                                                   failure_prob: f64,
+                                                   rng: R)
+                                                   -> StickySampler<K, R> {
     let two_t = 2.0 / error_tolerance * (1.0 / (support * failure_prob)).ln();
     StickySampler {
         error_tolerance: error_tolerance,
@@ -162,17 +275,30 @@ pub fn new_sampler(support: f64, error_tolerance: f64, failure_prob: f64) -> Sti
         t: two_t,
         s: HashMap::new(),
         n: 0.0,
+        rng: rng,
     }
 }
-impl StickySampler {
+impl<K: Hash + Eq + Clone, R: Rng> StickySampler<K, R> {
     fn prune(&mut self) {
-        let mut rng = thread_rng();
         // TODO: clean this up. go allows mutations
-        let mut to_remove: Vec<String> = vec![];
-        let mut to_decr: Vec<String> = vec![];
-        for (key, val) in &self.s {
+        let mut to_remove: Vec<K> = vec![];
+        let mut to_decr: Vec<K> = vec![];
+        // visit keys in a fixed order (by an unkeyed hash) instead of the
+        // map's randomly seeded order, so a seeded `rng` gives each key the
+        // same coin flips on every run.
+        let mut keys: Vec<(u64, &K)> = self.s
+            .keys()
+            .map(|key| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                (hasher.finish(), key)
+            })
+            .collect();
+        keys.sort_by_key(|&(hash, _)| hash);
+        for (_, key) in keys {
+            let val = &self.s[key];
             loop {
-                if rng.gen_weighted_bool(2) {
+                if self.rng.gen_bool(0.5) {
                     break;
                 }
                 let mut myval = *val;
@@ -192,9 +318,9 @@ impl StickySampler {
         }
     }
 }
-impl Counter for StickySampler {
+impl<K: Hash + Eq + Clone, R: Rng> Counter<K> for StickySampler<K, R> {
     /// return items who's frequency exceeds threshld
-    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry> {
+    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry<K>> {
         self.s
             .iter()
             .filter(|&(_, f)| *f >= (threshold - self.error_tolerance) * self.n)
@@ -207,7 +333,7 @@ impl Counter for StickySampler {
             .collect()
     }
     /// record that the given key has been observed.
-    fn observe(&mut self, key: &str) {
+    fn observe(&mut self, key: &K) {
         self.n += 1.0;
         let count = self.n;
         if count > self.t {
@@ -219,52 +345,432 @@ impl Counter for StickySampler {
             *val += 1.0;
             return;
         } else {
-            let mut rng = thread_rng();
-            let should_sample = rng.next_f64() <= 1.0 / self.r;
+            let should_sample = self.rng.gen::<f64>() <= 1.0 / self.r;
             if !should_sample {
                 return;
             }
         }
         // only arrive here for new elements which should be sampled
-        let k = key.to_string();
-        *self.s.entry(k).or_insert(0.0) += 1.0;
+        *self.s.entry(key.clone()).or_insert(0.0) += 1.0;
+    }
+    /// fold `other`'s counts into `self`. Both samplers track counts at
+    /// their own sampling rate `r`, so the less-thinned side's counts are
+    /// scaled down to the coarser (larger) `r` before being summed per key.
+    /// `r` and `t` are taken from whichever side is larger; `n` is summed,
+    /// since it counts every observation, not just the sampled ones.
+    fn merge(&mut self, other: &Self) {
+        let target_r = self.r.max(other.r);
+        let scale_self = self.r / target_r;
+        let scale_other = other.r / target_r;
+        let mut merged: HashMap<K, f64> = HashMap::new();
+        for (key, val) in &self.s {
+            *merged.entry(key.clone()).or_insert(0.0) += val * scale_self;
+        }
+        for (key, val) in &other.s {
+            *merged.entry(key.clone()).or_insert(0.0) += val * scale_other;
+        }
+        self.s = merged;
+        self.r = target_r;
+        self.t = self.t.max(other.t);
+        self.n += other.n;
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct StickySamplerState<K: Hash + Eq> {
+    error_tolerance: f64,
+    support: f64,
+    s: HashMap<K, f64>,
+    r: f64,
+    n: f64,
+    t: f64,
+}
+
+// Only `StickySampler<K, ThreadRng>` can be checkpointed: `R: Rng` isn't `Serialize`,
+// so a sampler built with `new_sampler_with_rng` for reproducible sampling can't be
+// saved or restored this way. `from_bytes` always resumes with a fresh `thread_rng()`.
+#[cfg(feature = "serde")]
+impl<K: Hash + Eq + Clone + Serialize + DeserializeOwned> Checkpoint for StickySampler<K, ThreadRng> {
+    fn to_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        let state = StickySamplerState {
+            error_tolerance: self.error_tolerance,
+            support: self.support,
+            s: self.s.clone(),
+            r: self.r,
+            n: self.n,
+            t: self.t,
+        };
+        serde_json::to_vec(&state)
+    }
+    fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        let state: StickySamplerState<K> = serde_json::from_slice(bytes)?;
+        Ok(StickySampler {
+            error_tolerance: state.error_tolerance,
+            support: state.support,
+            s: state.s,
+            r: state.r,
+            n: state.n,
+            t: state.t,
+            rng: thread_rng(),
+        })
+    }
+}
+
+/// `CountMinSketch` estimates item frequencies in a fixed `O(width * depth)`
+/// table, unlike `NaiveSampler`, `LossyCounter`, and `StickySampler`, which
+/// key on owned values in a `HashMap` that grows with cardinality. The
+/// heavy-hitter `candidates` set is pruned periodically against the current
+/// count, so it stays bounded instead of growing with every distinct key
+/// ever observed. See http://dimacs.rutgers.edu/~graham/pubs/papers/cm-full.pdf
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CountMinSketch<K: Hash + Eq> {
+    width: usize,
+    depth: usize,
+    table: Vec<u64>,
+    n: u64,
+    threshold: f64,
+    candidates: HashSet<K>,
+}
+
+/// `new_count_min_sketch` constructs a sketch sized for the given
+/// `(epsilon, delta)` accuracy parameters: `width = ceil(e/epsilon)` counters
+/// wide and `depth = ceil(ln(1/delta))` hash functions deep. `threshold` is
+/// the heavy-hitter frequency tracked incrementally as items are observed.
+pub fn new_count_min_sketch<K: Hash + Eq>(epsilon: f64, delta: f64, threshold: f64) -> CountMinSketch<K> {
+    let width = (::std::f64::consts::E / epsilon).ceil() as usize;
+    let depth = (1.0 / delta).ln().ceil() as usize;
+    CountMinSketch {
+        width: width,
+        depth: depth,
+        table: vec![0; width * depth],
+        n: 0,
+        threshold: threshold,
+        candidates: HashSet::new(),
+    }
+}
+impl<K: Hash + Eq + Clone> CountMinSketch<K> {
+    /// index of `key`'s counter in hash function `row`, one of `depth`
+    /// pairwise-independent hashes obtained by folding the row number in as
+    /// a salt before hashing the key.
+    fn index(&self, row: usize, key: &K) -> usize {
+        let mut hasher = DefaultHasher::new();
+        row.hash(&mut hasher);
+        key.hash(&mut hasher);
+        (hasher.finish() as usize) % self.width
+    }
+    /// the current estimated count for `key`: the minimum across all `depth`
+    /// rows, which cancels out any single row's hash collisions.
+    fn estimate(&self, key: &K) -> u64 {
+        (0..self.depth)
+            .map(|row| self.table[row * self.width + self.index(row, key)])
+            .min()
+            .unwrap_or(0)
+    }
+    /// drop any tracked candidate whose estimate has since fallen back
+    /// below `threshold * n`, since `observe` only ever adds to
+    /// `candidates` and never evicts one whose frequency has declined.
+    fn prune(&mut self) {
+        let bar = self.threshold * self.n as f64;
+        let candidates: Vec<K> = self.candidates.iter().cloned().collect();
+        self.candidates = candidates.into_iter()
+            .filter(|key| self.estimate(key) as f64 >= bar)
+            .collect();
+    }
+}
+impl<K: Hash + Eq + Clone> Counter<K> for CountMinSketch<K> {
+    /// return items who's frequency exceeds threshld, drawn from the
+    /// heavy-hitter candidate set, since the sketch can't enumerate its keys.
+    fn items_above_threshold(&self, threshold: f64) -> Vec<Entry<K>> {
+        let f_n = self.n as f64;
+        self.candidates
+            .iter()
+            .filter_map(|key| {
+                let frequency = self.estimate(key) as f64 / f_n;
+                if frequency >= threshold {
+                    Some(Entry {
+                        key: key.clone(),
+                        frequency: frequency,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+    /// record that the given key has been observed, and track it as a
+    /// heavy-hitter candidate if its estimated count has crossed
+    /// `threshold * n`. Re-checks existing candidates every `width`
+    /// observations so keys whose frequency has since fallen back below
+    /// the bar don't linger forever.
+    fn observe(&mut self, key: &K) {
+        self.n += 1;
+        let mut min_count = u64::max_value();
+        for row in 0..self.depth {
+            let idx = row * self.width + self.index(row, key);
+            self.table[idx] += 1;
+            if self.table[idx] < min_count {
+                min_count = self.table[idx];
+            }
+        }
+        if min_count as f64 >= self.threshold * self.n as f64 {
+            self.candidates.insert(key.clone());
+        }
+        if self.n % self.width as u64 == 0 {
+            self.prune();
+        }
+    }
+    /// fold `other`'s counts into `self`, assuming both sketches share the
+    /// same `width`/`depth`: the tables are summed cell-wise, `n` is
+    /// summed, and the candidate set is recomputed against the merged bar.
+    fn merge(&mut self, other: &Self) {
+        for (slot, other_slot) in self.table.iter_mut().zip(other.table.iter()) {
+            *slot += *other_slot;
+        }
+        self.n += other.n;
+        let mut candidates: Vec<K> = self.candidates.iter().cloned().collect();
+        candidates.extend(other.candidates.iter().cloned());
+        let bar = self.threshold * self.n as f64;
+        self.candidates = candidates.into_iter()
+            .filter(|key| self.estimate(key) as f64 >= bar)
+            .collect();
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K: Hash + Eq + Serialize + DeserializeOwned> Checkpoint for CountMinSketch<K> {
+    fn to_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(self)
     }
+    fn from_bytes(bytes: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+/// `report_periodically` spawns a background thread that, every `interval`,
+/// emits each item above `threshold` as a `countish_heavy_hitter` gauge
+/// labeled by key, through the `metrics` facade.
+#[cfg(feature = "metrics")]
+pub fn report_periodically<K, T>(counter: Arc<Mutex<T>>,
+                                  threshold: f64,
+                                  interval: Duration)
+                                  -> thread::JoinHandle<()>
+    where K: Hash + Eq + Clone + ToString,
+          T: Counter<K> + Send + 'static
+{
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let entries = counter.lock().unwrap().items_above_threshold(threshold);
+        for entry in entries {
+            metrics::gauge!("countish_heavy_hitter", entry.frequency, "key" => entry.key.to_string());
+        }
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use ::Counter;
+    #[cfg(feature = "serde")]
+    use ::Checkpoint;
     #[test]
     fn naive() {
-        let mut sampler = ::NaiveSampler { ..Default::default() };
+        let mut sampler = ::new_naive_sampler();
         for _ in 1..10 {
-            sampler.observe("shane");
+            sampler.observe(&"shane".to_string());
         }
-        sampler.observe("hansen");
+        sampler.observe(&"hansen".to_string());
         let items = sampler.items_above_threshold(0.5);
         assert_eq!(items.len(), 1);
         assert_eq!(items[0].key, "shane");
     }
     #[test]
+    fn naive_merge() {
+        let mut a = ::new_naive_sampler();
+        let mut b = ::new_naive_sampler();
+        for _ in 0..5 {
+            a.observe(&"shane".to_string());
+            b.observe(&"shane".to_string());
+        }
+        a.merge(&b);
+        let items = a.items_above_threshold(0.5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "shane");
+        assert!(items[0].frequency <= 1.0);
+    }
+    #[test]
     fn lossy() {
         let mut sampler = ::new_lossy_counter(0.01, 0.005);
         for _ in 1..10 {
-            sampler.observe("shane");
+            sampler.observe(&"shane".to_string());
         }
-        sampler.observe("hansen");
+        sampler.observe(&"hansen".to_string());
         let items = sampler.items_above_threshold(0.5);
         assert_eq!(items.len(), 1);
         assert_eq!(items[0].key, "shane");
     }
     #[test]
+    #[cfg(feature = "serde")]
+    fn lossy_checkpoint_round_trip() {
+        let mut sampler = ::new_lossy_counter(0.01, 0.005);
+        for _ in 1..10 {
+            sampler.observe(&"shane".to_string());
+        }
+        sampler.observe(&"hansen".to_string());
+        let bytes = sampler.to_bytes().unwrap();
+        let mut restored = ::LossyCounter::<String>::from_bytes(&bytes).unwrap();
+        // observing the same way on both sides should keep them in
+        // agreement, proving `n`/`bucket_width` (and so bucket placement)
+        // round-tripped rather than resetting.
+        sampler.observe(&"shane".to_string());
+        restored.observe(&"shane".to_string());
+        let restored_items = restored.items_above_threshold(0.5);
+        assert_eq!(restored_items.len(), 1);
+        assert_eq!(restored_items[0].key, "shane");
+        assert_eq!(restored_items[0].frequency, sampler.items_above_threshold(0.5)[0].frequency);
+    }
+    #[test]
+    fn lossy_merge() {
+        let mut a = ::new_lossy_counter(0.01, 0.005);
+        let mut b = ::new_lossy_counter(0.01, 0.005);
+        for _ in 0..5 {
+            a.observe(&"shane".to_string());
+            b.observe(&"shane".to_string());
+        }
+        a.observe(&"hansen".to_string());
+        b.observe(&"hansen".to_string());
+        a.merge(&b);
+        let items = a.items_above_threshold(0.5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "shane");
+        assert!(items[0].frequency <= 1.0);
+    }
+    #[test]
     fn sticky() {
         let mut sampler = ::new_sampler(0.1, 0.1, 0.01);
         for _ in 1..10 {
-            sampler.observe("shane");
+            sampler.observe(&"shane".to_string());
         }
-        sampler.observe("hansen");
+        sampler.observe(&"hansen".to_string());
         let items = sampler.items_above_threshold(0.5);
         assert_eq!(items.len(), 1, "asd");
         assert_eq!(items[0].key, "shane");
     }
+    #[test]
+    #[cfg(feature = "serde")]
+    fn sticky_checkpoint_round_trip() {
+        let mut sampler = ::new_sampler(0.1, 0.1, 0.01);
+        for _ in 1..10 {
+            sampler.observe(&"shane".to_string());
+        }
+        sampler.observe(&"hansen".to_string());
+        let bytes = sampler.to_bytes().unwrap();
+        let mut restored = ::StickySampler::<String, ::rand::rngs::ThreadRng>::from_bytes(&bytes).unwrap();
+        // `r`, `t`, and `n` round-tripping means the restored sampler keeps
+        // applying the same sampling-probability bookkeeping as the original.
+        assert_eq!(restored.items_above_threshold(0.5).len(),
+                   sampler.items_above_threshold(0.5).len());
+        for _ in 0..9 {
+            sampler.observe(&"shane".to_string());
+            restored.observe(&"shane".to_string());
+        }
+        let restored_items = restored.items_above_threshold(0.5);
+        assert_eq!(restored_items.len(), 1);
+        assert_eq!(restored_items[0].key, "shane");
+    }
+    #[test]
+    fn sticky_merge() {
+        let mut a = ::new_sampler(0.01, 0.1, 0.01);
+        let mut b = ::new_sampler(0.01, 0.1, 0.01);
+        for _ in 1..10 {
+            a.observe(&"hot".to_string());
+            b.observe(&"hot".to_string());
+        }
+        a.observe(&"cold".to_string());
+        b.observe(&"cold".to_string());
+        a.merge(&b);
+        let items = a.items_above_threshold(0.5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "hot");
+        assert!(items[0].frequency > 0.0 && items[0].frequency <= 1.0);
+    }
+    #[test]
+    fn sticky_merge_diverged_sampling_rates() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        let support = 0.01;
+        let error_tolerance = 0.1;
+        let failure_prob = 0.1;
+        let mut small = ::new_sampler_with_rng(support, error_tolerance, failure_prob, StdRng::seed_from_u64(1));
+        for i in 0..300 {
+            let key = if i % 2 == 0 { "hot" } else { "cold" };
+            small.observe(&key.to_string());
+        }
+        let mut large = ::new_sampler_with_rng(support, error_tolerance, failure_prob, StdRng::seed_from_u64(2));
+        for i in 0..30000 {
+            let key = if i % 2 == 0 { "hot" } else { "cold" };
+            large.observe(&key.to_string());
+        }
+        assert!(small.r < large.r, "test setup should diverge sampling rates");
+        small.merge(&large);
+        let items = small.items_above_threshold(0.0);
+        let hot = items.iter().find(|e| e.key == "hot").expect("hot should be tracked");
+        let true_frequency = 0.5;
+        assert!((hot.frequency - true_frequency).abs() <= error_tolerance + support,
+                "merged frequency {} should be within the algorithm's error bound of {}",
+                hot.frequency,
+                true_frequency);
+    }
+    #[test]
+    fn sticky_seeded_rng_is_deterministic() {
+        use rand::SeedableRng;
+        use rand::rngs::StdRng;
+        let build = || {
+            let mut sampler = ::new_sampler_with_rng(0.1, 0.1, 0.01, StdRng::seed_from_u64(42));
+            for _ in 1..10 {
+                sampler.observe(&"shane".to_string());
+            }
+            sampler.observe(&"hansen".to_string());
+            sampler
+        };
+        let a = build();
+        let b = build();
+        assert_eq!(a.n, b.n);
+        assert_eq!(a.r, b.r);
+        assert_eq!(a.s, b.s);
+    }
+    #[test]
+    fn count_min() {
+        let mut sketch = ::new_count_min_sketch(0.01, 0.01, 0.5);
+        for _ in 1..10 {
+            sketch.observe(&"shane".to_string());
+        }
+        sketch.observe(&"hansen".to_string());
+        let items = sketch.items_above_threshold(0.5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, "shane");
+    }
+    #[test]
+    fn count_min_candidates_stay_bounded() {
+        let mut sketch = ::new_count_min_sketch(0.01, 0.01, 0.0001);
+        for i in 0..2000 {
+            sketch.observe(&i.to_string());
+        }
+        for _ in 0..200_000 {
+            sketch.observe(&"dominant".to_string());
+        }
+        assert!(sketch.candidates.len() < 100);
+    }
+    #[test]
+    fn naive_counts_non_string_keys() {
+        // eg. counting raw u32 IP addresses without a to_string() allocation.
+        let mut sampler = ::new_naive_sampler();
+        for _ in 1..10 {
+            sampler.observe(&1u32);
+        }
+        sampler.observe(&2u32);
+        let items = sampler.items_above_threshold(0.5);
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].key, 1u32);
+    }
 }
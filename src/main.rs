@@ -3,19 +3,93 @@ extern crate getopts;
 
 use getopts::Options;
 use std::env;
-use countish::{new_lossy_counter, Counter, new_naive_sampler, new_sampler, Entry};
+use countish::{new_count_min_sketch, new_lossy_counter, Counter, new_naive_sampler, new_sampler,
+               Entry};
+#[cfg(feature = "serde")]
+use countish::Checkpoint;
 use std::io::prelude::*;
 use std::io;
-fn process<T: Counter>(mut counter: T, threshold: f64) -> Vec<Entry> {
+#[cfg(feature = "metrics")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "metrics")]
+use std::time::Duration;
+
+fn process<T: Counter<String>>(mut counter: T) -> T {
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
         if let Ok(line) = line {
             counter.observe(&line);
         }
     }
+    counter
+}
+
+#[cfg(feature = "serde")]
+fn load_checkpoint<T: Checkpoint>(path: &str) -> T {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .expect("failed to read --load-state file");
+    T::from_bytes(&bytes).expect("failed to parse --load-state file")
+}
+
+#[cfg(feature = "serde")]
+fn save_checkpoint<T: Checkpoint>(counter: &T, path: &str) {
+    let bytes = counter.to_bytes().expect("failed to serialize state");
+    std::fs::File::create(path)
+        .and_then(|mut f| f.write_all(&bytes))
+        .expect("failed to write --save-state file");
+}
+
+#[cfg(feature = "serde")]
+fn run<T, F>(new: F,
+              load_state: &Option<String>,
+              save_state: &Option<String>,
+              threshold: f64)
+              -> Vec<Entry<String>>
+    where T: Counter<String> + Checkpoint,
+          F: FnOnce() -> T
+{
+    let counter = match *load_state {
+        Some(ref path) => load_checkpoint(path),
+        None => new(),
+    };
+    let counter = process(counter);
+    if let Some(ref path) = *save_state {
+        save_checkpoint(&counter, path);
+    }
     counter.items_above_threshold(threshold)
 }
 
+#[cfg(not(feature = "serde"))]
+fn run<T, F>(new: F,
+              _load_state: &Option<String>,
+              _save_state: &Option<String>,
+              threshold: f64)
+              -> Vec<Entry<String>>
+    where T: Counter<String>,
+          F: FnOnce() -> T
+{
+    process(new()).items_above_threshold(threshold)
+}
+
+#[cfg(feature = "metrics")]
+fn run_with_metrics<T, F>(new: F, threshold: f64, interval: Duration) -> Vec<Entry<String>>
+    where T: Counter<String> + Send + 'static,
+          F: FnOnce() -> T
+{
+    let counter = Arc::new(Mutex::new(new()));
+    countish::report_periodically(counter.clone(), threshold, interval);
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        if let Ok(line) = line {
+            counter.lock().unwrap().observe(&line);
+        }
+    }
+    let entries = counter.lock().unwrap().items_above_threshold(threshold);
+    entries
+}
+
 fn print_usage(program: &str, opts: Options) {
     let brief = format!("Usage: {} FILE [options]", program);
     print!("{}", opts.usage(&brief));
@@ -34,16 +108,34 @@ pub fn main() {
     opts.optopt("",
                 "error-tolerance",
                 "",
-                "Tolerable error (eg .01 for 1%). Impls:stucky, lossy");
+                "Tolerable error (eg .01 for 1%). Impls:stucky, lossy, countmin (epsilon)");
     opts.optopt("",
                 "failure-prob",
                 "",
-                "Chances that incorrect results will be published. Impls:sticky");
+                "Chances that incorrect results will be published. Impls:sticky, countmin \
+                 (delta)");
     opts.optopt("",
                 "threshold",
                 "",
                 "frequency threshold: return entries who's frequency exceeds this.");
-    opts.optopt("", "impl", "", "One of sticky|naive|lossy");
+    opts.optopt("", "impl", "", "One of sticky|naive|lossy|countmin");
+    opts.optopt("",
+                "load-state",
+                "",
+                "Resume from a counter state checkpointed with --save-state. Requires the \
+                 `serde` feature.");
+    opts.optopt("",
+                "save-state",
+                "",
+                "Checkpoint counter state to FILE at stdin EOF so a later run can resume with \
+                 --load-state. Requires the `serde` feature.");
+    opts.optopt("",
+                "metrics-interval",
+                "",
+                "Continuously publish heavy hitters as gauges every SECS seconds, running \
+                 stdin processing as a long-lived service instead of a one-shot batch job. \
+                 Requires the `metrics` feature; not supported for --impl sticky or combined \
+                 with --load-state/--save-state.");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
         Err(f) => panic!(f.to_string()),
@@ -71,13 +163,69 @@ pub fn main() {
     if let Some(m) = matches.opt_str("impl") {
         implementation = m;
     }
+    let load_state = matches.opt_str("load-state");
+    let save_state = matches.opt_str("save-state");
+    if !cfg!(feature = "serde") && (load_state.is_some() || save_state.is_some()) {
+        panic!("--load-state/--save-state require building with the `serde` feature");
+    }
+    let metrics_interval: Option<u64> = matches.opt_str("metrics-interval")
+        .map(|val| val.parse().unwrap());
+    if !cfg!(feature = "metrics") && metrics_interval.is_some() {
+        panic!("--metrics-interval requires building with the `metrics` feature");
+    }
+    if metrics_interval.is_some() && (load_state.is_some() || save_state.is_some()) {
+        panic!("--metrics-interval cannot be combined with --load-state/--save-state yet");
+    }
     let entries = match implementation.as_ref() {
-        "lossy" => process(new_lossy_counter(support, error_tolerance), threshold),
+        "lossy" => {
+            match metrics_interval {
+                #[cfg(feature = "metrics")]
+                Some(secs) => {
+                    run_with_metrics(|| new_lossy_counter(support, error_tolerance),
+                                     threshold,
+                                     Duration::from_secs(secs))
+                }
+                _ => {
+                    run(|| new_lossy_counter(support, error_tolerance),
+                        &load_state,
+                        &save_state,
+                        threshold)
+                }
+            }
+        }
         "sticky" => {
-            process(new_sampler(support, error_tolerance, failure_prob),
-                    threshold)
+            if metrics_interval.is_some() {
+                panic!("--metrics-interval is not supported for --impl sticky (its thread-local \
+                        RNG can't be shared across threads)");
+            }
+            run(|| new_sampler(support, error_tolerance, failure_prob),
+                &load_state,
+                &save_state,
+                threshold)
+        }
+        "naive" => {
+            match metrics_interval {
+                #[cfg(feature = "metrics")]
+                Some(secs) => run_with_metrics(new_naive_sampler, threshold, Duration::from_secs(secs)),
+                _ => run(new_naive_sampler, &load_state, &save_state, threshold),
+            }
+        }
+        "countmin" => {
+            match metrics_interval {
+                #[cfg(feature = "metrics")]
+                Some(secs) => {
+                    run_with_metrics(|| new_count_min_sketch(error_tolerance, failure_prob, threshold),
+                                     threshold,
+                                     Duration::from_secs(secs))
+                }
+                _ => {
+                    run(|| new_count_min_sketch(error_tolerance, failure_prob, threshold),
+                        &load_state,
+                        &save_state,
+                        threshold)
+                }
+            }
         }
-        "naive" => process(new_naive_sampler(), threshold),
         _ => panic!("unknown implementation"),
 
     };